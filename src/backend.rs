@@ -0,0 +1,68 @@
+// The pluggable storage engine. `DiskTable` (see main.rs) is the
+// commitlog+sstable engine; `FileTable` here is a second, much simpler
+// whole-file engine, so a database can be migrated between the two (or
+// between on-disk format versions of the same one) via `convert_backend`
+// without either engine needing to know the other exists.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::data::Data;
+
+pub trait StorageBackend {
+    fn open(path: &Path) -> Self where Self: Sized;
+    fn get(&self, key: &Data) -> Option<Data>;
+    fn put(&mut self, key: &Data, val: &Data);
+    fn delete(&mut self, key: &Data) -> bool;
+    fn scan(&self, start: &Data, end: &Data) -> Vec<(Data, Data)>;
+    // Every live key/value pair, with no bound on key range. `scan` takes
+    // inclusive start/end bounds, which has no finite value that is
+    // guaranteed to sort after every possible binary key - callers that
+    // want "everything" (export, convert) need this instead.
+    fn scan_all(&self) -> Vec<(Data, Data)>;
+    fn flush(&mut self) {}
+}
+
+// The whole database as one CBOR-encoded file, rewritten in full on every
+// flush. No commitlog, no levels, no bloom filters - just the simplest
+// possible engine, useful as a migration source/target and a baseline to
+// compare the sstable engine against.
+pub struct FileTable {
+    path: PathBuf,
+    db: BTreeMap<Data, Data>,
+}
+
+impl StorageBackend for FileTable {
+    fn open(path: &Path) -> FileTable {
+        let db = if path.exists() {
+            let mut f = File::open(path).expect("Failed to open FileTable");
+            let mut buf = Vec::new();
+            f.read_to_end(&mut buf).expect("Failed to read FileTable");
+            serde_cbor::from_slice(&buf).expect("Failed to deserialize FileTable")
+        } else {
+            BTreeMap::new()
+        };
+        FileTable { path: path.to_path_buf(), db }
+    }
+    fn get(&self, key: &Data) -> Option<Data> {
+        self.db.get(key).cloned()
+    }
+    fn put(&mut self, key: &Data, val: &Data) {
+        self.db.insert(key.clone(), val.clone());
+    }
+    fn delete(&mut self, key: &Data) -> bool {
+        self.db.remove(key).is_some()
+    }
+    fn scan(&self, start: &Data, end: &Data) -> Vec<(Data, Data)> {
+        self.db.range(start.clone()..=end.clone()).map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+    fn scan_all(&self) -> Vec<(Data, Data)> {
+        self.db.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+    fn flush(&mut self) {
+        let mut f = File::create(&self.path).expect("Failed to create FileTable");
+        f.write_all(&serde_cbor::to_vec(&self.db).expect("Failed to serialize FileTable")).expect("Failed to write FileTable");
+    }
+}
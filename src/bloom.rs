@@ -0,0 +1,121 @@
+// A Bloom filter over all the keys in one sstable file, so a negative
+// lookup can be answered without touching disk at all. Built once when the
+// file is written and persisted as a sidecar so it can be loaded back
+// whole on open, rather than recomputed.
+//
+// Uses k independent hash functions derived from a single 64-bit hash via
+// double hashing (h_i = h1 + i*h2 mod m), as in Kirsch & Mitzenmacher and
+// LevelDB's `BloomFilterPolicy`.
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// ~1% false positive rate.
+pub const DEFAULT_BITS_PER_KEY: u64 = 10;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    m: u64,
+    k: u32,
+}
+
+impl BloomFilter {
+    pub fn build<'a, I: Iterator<Item = &'a [u8]>>(keys: I, bits_per_key: u64) -> BloomFilter {
+        let keys: Vec<&[u8]> = keys.collect();
+        let m = std::cmp::max(keys.len() as u64 * bits_per_key, 64);
+        let k = std::cmp::min(
+            30,
+            std::cmp::max(1, (bits_per_key as f64 * std::f64::consts::LN_2).round() as u32),
+        );
+        let bits = vec![0u8; ((m + 7) / 8) as usize];
+        let mut filter = BloomFilter { bits, m, k };
+        for key in &keys {
+            filter.insert(key);
+        }
+        filter
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        let (h1, h2) = Self::hash_pair(key);
+        let mut h1 = h1;
+        for _ in 0..self.k {
+            let bit = (h1 % self.m) as usize;
+            self.bits[bit / 8] |= 1 << (bit % 8);
+            h1 = h1.wrapping_add(h2);
+        }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let (mut h1, h2) = Self::hash_pair(key);
+        for _ in 0..self.k {
+            let bit = (h1 % self.m) as usize;
+            if self.bits[bit / 8] & (1 << (bit % 8)) == 0 {
+                return false;
+            }
+            h1 = h1.wrapping_add(h2);
+        }
+        true
+    }
+
+    fn hash_pair(key: &[u8]) -> (u64, u64) {
+        let h = fnv1a(key);
+        // Spread a single hash into two independent-enough halves for
+        // double hashing, per Kirsch & Mitzenmacher.
+        let h1 = h;
+        let h2 = h.rotate_left(31) | 1;
+        (h1, h2)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        f.write_all(&serde_cbor::to_vec(self).expect("Failed to serialize bloom filter"))?;
+        f.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<BloomFilter> {
+        let mut f = File::open(path)?;
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf)?;
+        Ok(serde_cbor::from_slice(&buf).expect("Failed to deserialize bloom filter"))
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = OFFSET_BASIS;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_every_inserted_key() {
+        let keys: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let filter = BloomFilter::build(keys.iter().map(|k| k.as_slice()), DEFAULT_BITS_PER_KEY);
+        for key in &keys {
+            assert!(filter.contains(key));
+        }
+    }
+
+    #[test]
+    fn false_positive_rate_is_close_to_the_advertised_one_percent() {
+        let present: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let filter = BloomFilter::build(present.iter().map(|k| k.as_slice()), DEFAULT_BITS_PER_KEY);
+        let absent = (1_000_000u32..1_010_000u32).map(|i| i.to_be_bytes().to_vec());
+        let false_positives = absent.filter(|k| filter.contains(k)).count();
+        // ~1% expected; leave generous headroom so this doesn't flake.
+        assert!(false_positives < 500, "false positive rate too high: {}/10000", false_positives);
+    }
+}
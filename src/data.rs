@@ -0,0 +1,66 @@
+// An arbitrary binary key or value. Everything upstream of this point used
+// to be a `String`, which panicked on any sstable key or value that wasn't
+// valid UTF-8; `Data` just wraps the raw bytes instead, with cheap clones
+// (an `Arc`, not a copy of the bytes) since a key is cloned constantly
+// while threading through `mutations`, batches, and merges. `Arc` over
+// `Rc` costs an atomic refcount instead of a plain one, but keeps `Data`
+// `Send`, which a background compaction (see `DiskTable::maybe_compact`)
+// needs in order to hand file metadata (whose key bounds are `Data`) to a
+// worker thread and back at all.
+//
+// Serialized as a CBOR byte string rather than via the derived `Vec<u8>`
+// sequence encoding, so on-disk values stay compact.
+
+use std::fmt;
+use std::sync::Arc;
+
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Data(Arc<Vec<u8>>);
+
+impl Data {
+    pub fn from_bytes(bytes: Vec<u8>) -> Data {
+        Data(Arc::new(bytes))
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+    // A best-effort textual rendering for the REPL; invalid UTF-8 is
+    // replaced rather than panicking.
+    pub fn display(&self) -> String {
+        String::from_utf8_lossy(&self.0).into_owned()
+    }
+}
+
+impl fmt::Debug for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Data({})", self.display())
+    }
+}
+
+impl Serialize for Data {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Data {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Data, D::Error> {
+        struct DataVisitor;
+        impl<'de> Visitor<'de> for DataVisitor {
+            type Value = Data;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a byte string")
+            }
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Data, E> {
+                Ok(Data::from_bytes(v.to_vec()))
+            }
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Data, E> {
+                Ok(Data::from_bytes(v))
+            }
+        }
+        deserializer.deserialize_bytes(DataVisitor)
+    }
+}
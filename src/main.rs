@@ -1,4 +1,4 @@
-use std::io;
+use std::io::{self, Read, Write};
 use std::path;
 use std::collections::BTreeMap;
 use std::fs::File;
@@ -8,25 +8,183 @@ extern crate serde;
 extern crate serde_cbor;
 extern crate sstable;
 
+mod backend;
+mod bloom;
+mod data;
+mod version;
+
 use commitlog::*;
 use serde::{Serialize, Deserialize};
 use commitlog::message::MessageSet;
 use sstable::SSIterator;
-use std::cmp::Ordering;
+use backend::{FileTable, StorageBackend};
+use bloom::BloomFilter;
+use data::Data;
+use version::{FileMetadata, FileNumber, VersionEdit, VersionSet};
+
+// A monotonic counter assigned to every mutation as it is committed, so that
+// reads can be pinned to "everything committed at or before sequence N".
+// Mirrors LevelDB's SequenceNumber.
+type SequenceNumber = u64;
+
+// A read handle pinned to a point in the sequence history. Obtained from
+// `DiskTable::snapshot` and released with `DiskTable::release_snapshot`;
+// while a snapshot is outstanding, compaction will not discard any version
+// it can still see (see `DiskTable::compaction_floor`).
+//
+// `None` means the snapshot was taken before the first write ever
+// committed - distinct from `Some(0)`, which is a real, assignable
+// sequence number (the very first commit gets seq 0). Conflating the two
+// would make a snapshot taken on an empty database wrongly see that first
+// write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    seq: Option<SequenceNumber>,
+}
+
+impl Snapshot {
+    fn sequence(&self) -> Option<SequenceNumber> {
+        self.seq
+    }
+}
 
 // TODO: Split into "command" and "query"
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 enum Command {
-    SELECT(String),
-    INSERT { key: String, val: String },
-    DELETE(String),
+    SELECT { key: Data, as_of: Option<SequenceNumber> },
+    INSERT { key: Data, val: Data },
+    DELETE(Data),
+    BATCH(WriteBatch),
+    SCAN { start: Data, end: Data },
     DUMP,
     COMPACT,
+    EXPORT(String),
+    IMPORT(String),
+    // Pin the sequence currently visible to a live read, and release a
+    // previously pinned one. Only meaningful on backends with a notion of
+    // history (see `DiskTable::snapshot`); the value is the sequence
+    // itself, printed back so the caller can `.release` it later - or
+    // `none` if the snapshot was taken before any write ever committed.
+    SNAPSHOT,
+    RELEASE(Option<SequenceNumber>),
     EXIT,
 }
 
+// One operation within a `WriteBatch`. Unlike `Command`, this never stands
+// alone in the commitlog; a whole batch is always persisted as a single
+// `Record::Batch`. `Insert` is an upsert - see `DiskTable::write`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum BatchOp {
+    Insert { key: Data, val: Data },
+    Delete { key: Data },
+}
+
+// A sequence of INSERT/DELETE operations to apply atomically: one
+// commitlog append and one assigned sequence range for the whole batch,
+// instead of a flush() per operation. Mirrors LevelDB's `WriteBatch`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    fn new() -> WriteBatch {
+        Default::default()
+    }
+    fn insert(&mut self, key: &Data, val: &Data) -> &mut WriteBatch {
+        self.ops.push(BatchOp::Insert { key: key.clone(), val: val.clone() });
+        self
+    }
+    fn delete(&mut self, key: &Data) -> &mut WriteBatch {
+        self.ops.push(BatchOp::Delete { key: key.clone() });
+        self
+    }
+}
+
+// A single commitlog entry. A `Single` is one committed `Command`, tagged
+// with its sequence number so recovery and snapshot reads can reconstruct
+// "as of" visibility; a `Batch` is a whole `WriteBatch`, persisted as one
+// message so it replays all-or-nothing and its ops share one sequence
+// range starting at `start_seq`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum Record {
+    Single { seq: SequenceNumber, cmd: Command },
+    Batch { start_seq: SequenceNumber, ops: Vec<BatchOp> },
+}
+
+// What a key resolved to as of the sequence that last touched it in a
+// given sstable file: either a value, or a tombstone recording that the
+// key was deleted. The tombstone has to be persisted (not just omitted)
+// now that a key can live in more than one file across levels — otherwise
+// a delete in a newer file could never shadow the value still sitting in
+// an older one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum SealedEntry {
+    Value(Data),
+    Tombstone,
+}
+
+// The value shape written into the sstable: an entry tagged with the
+// sequence number that committed it, so historical reads stay correct
+// across compactions instead of losing provenance once folded to disk.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SealedRecord {
+    seq: SequenceNumber,
+    entry: SealedEntry,
+}
+
+// Hand-rolled hex/base64 decoding for binary literals typed at the REPL -
+// this crate has no dependency that provides either, and both are short
+// enough to write directly (see `bloom::fnv1a` for the same tradeoff).
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    let chars: Vec<char> = s.chars().collect();
+    let mut bytes = Vec::with_capacity(chars.len() / 2);
+    for pair in chars.chunks(2) {
+        let byte_str: String = pair.iter().collect();
+        bytes.push(u8::from_str_radix(&byte_str, 16).ok()?);
+    }
+    Some(bytes)
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn parse_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut bits: u32 = 0;
+    let mut nbits: u32 = 0;
+    let mut out = Vec::new();
+    for c in s.bytes() {
+        let val = BASE64_ALPHABET.iter().position(|&b| b == c)? as u32;
+        bits = (bits << 6) | val;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            out.push((bits >> nbits) as u8);
+        }
+    }
+    Some(out)
+}
+
+// Parse one REPL token into binary `Data`: `0x<hex>` or `b64:<base64>` for
+// arbitrary bytes, anything else taken as a literal UTF-8 string.
+fn parse_data(tok: &str) -> Option<Data> {
+    if let Some(hex) = tok.strip_prefix("0x") {
+        return parse_hex(hex).map(Data::from_bytes);
+    }
+    if let Some(b64) = tok.strip_prefix("b64:") {
+        return parse_base64(b64).map(Data::from_bytes);
+    }
+    Some(Data::from_bytes(tok.as_bytes().to_vec()))
+}
+
 impl Command {
     fn parse(cmd: &String) -> Option<Command> {
+        if let Some(rest) = cmd.trim_start().strip_prefix(".batch") {
+            return Self::parse_batch(rest);
+        }
         let mut tokens = cmd.split_whitespace();
         match tokens.next() {
             Some(cmd) => {
@@ -34,15 +192,45 @@ impl Command {
                     ".dump" => Some(Command::DUMP),
                     ".exit" => Some(Command::EXIT),
                     ".compact" => Some(Command::COMPACT),
+                    ".export" => tokens.next().map(|p| Command::EXPORT(p.to_string())),
+                    ".import" => tokens.next().map(|p| Command::IMPORT(p.to_string())),
+                    ".snapshot" => Some(Command::SNAPSHOT),
+                    ".release" => tokens.next().map(|s| {
+                        if s.eq_ignore_ascii_case("none") {
+                            Some(None)
+                        } else {
+                            s.parse().ok().map(Some)
+                        }
+                    }).flatten().map(Command::RELEASE),
                     _ => match tokens.next() {
                         Some(arg) => {
                             match cmd {
-                                "select" | "SELECT" => Some(Command::SELECT(arg.to_string())),
+                                "select" | "SELECT" => {
+                                    let as_of = match tokens.next() {
+                                        None => None,
+                                        Some(t) if t.eq_ignore_ascii_case("as") => match tokens.next() {
+                                            Some(of) if of.eq_ignore_ascii_case("of") => match tokens.next() {
+                                                Some(seq) => match seq.parse::<SequenceNumber>() {
+                                                    Ok(n) => Some(n),
+                                                    Err(_) => return None,
+                                                },
+                                                None => return None,
+                                            },
+                                            _ => return None,
+                                        },
+                                        Some(_) => return None,
+                                    };
+                                    Some(Command::SELECT { key: parse_data(arg)?, as_of })
+                                }
                                 "insert" | "INSERT" => match tokens.next() {
                                     None => None,
-                                    Some(val) => Some(Command::INSERT { key: arg.to_string(), val: val.to_string() }),
+                                    Some(val) => Some(Command::INSERT { key: parse_data(arg)?, val: parse_data(val)? }),
+                                }
+                                "delete" | "DELETE" => Some(Command::DELETE(parse_data(arg)?)),
+                                "scan" | "SCAN" => match tokens.next() {
+                                    None => None,
+                                    Some(end) => Some(Command::SCAN { start: parse_data(arg)?, end: parse_data(end)? }),
                                 }
-                                "delete" | "DELETE" => Some(Command::DELETE(arg.to_string())),
                                 _ => None,
                             }
                         }
@@ -53,11 +241,41 @@ impl Command {
             None => None,
         }
     }
+    // Parses `insert k v; delete k; ...` into a `WriteBatch`.
+    fn parse_batch(rest: &str) -> Option<Command> {
+        let mut batch = WriteBatch::new();
+        for part in rest.split(';') {
+            let part = part.trim();
+            if part.is_empty() {
+                continue;
+            }
+            let mut tokens = part.split_whitespace();
+            match tokens.next()? {
+                "insert" | "INSERT" => {
+                    let key = parse_data(tokens.next()?)?;
+                    let val = parse_data(tokens.next()?)?;
+                    batch.insert(&key, &val);
+                }
+                "delete" | "DELETE" => {
+                    let key = parse_data(tokens.next()?)?;
+                    batch.delete(&key);
+                }
+                _ => return None,
+            }
+        }
+        Some(Command::BATCH(batch))
+    }
     fn execute(&self, db: &mut DatabaseTable) {
         match self {
-            Command::SELECT(key) => match db.select(key) {
-                None => { println!("Not found") }
-                Some(val) => { println!("{}", val) }
+            Command::SELECT { key, as_of } => {
+                let result = match as_of {
+                    Some(seq) => db.select_as_of(key, *seq),
+                    None => db.select(key),
+                };
+                match result {
+                    None => { println!("Not found") }
+                    Some(val) => { println!("{}", val.display()) }
+                }
             }
             Command::INSERT { key, val } => {
                 if db.insert(key, val) {
@@ -73,12 +291,21 @@ impl Command {
                     println!("Not found")
                 }
             }
+            Command::BATCH(batch) => {
+                db.write(batch.clone());
+                println!("Succeeded")
+            }
+            Command::SCAN { start, end } => {
+                for (key, val) in db.scan(start, end) {
+                    println!("{} = {}", key.display(), val.display())
+                }
+            }
             _ => ()
         }
     }
-    fn key(&self) -> Option<&String> {
+    fn key(&self) -> Option<&Data> {
         match self {
-            Command::SELECT(key) => Some(key),
+            Command::SELECT { key, as_of: _ } => Some(key),
             Command::INSERT { key, val: _ } => Some(key),
             Command::DELETE(key) => Some(key),
             _ => None
@@ -87,14 +314,32 @@ impl Command {
 }
 
 trait DatabaseTable {
-    fn insert(&mut self, key: &str, val: &str) -> bool;
-    fn select(&self, key: &str) -> Option<String>;
-    fn delete(&mut self, key: &str) -> bool;
+    fn insert(&mut self, key: &Data, val: &Data) -> bool;
+    fn select(&self, key: &Data) -> Option<Data>;
+    fn delete(&mut self, key: &Data) -> bool;
+    // Read `key` as it stood at `seq`, ignoring any mutation committed
+    // after it. Backends with no notion of history just ignore `seq`.
+    fn select_as_of(&self, key: &Data, seq: SequenceNumber) -> Option<Data> {
+        let _ = seq;
+        self.select(key)
+    }
+    // Apply every op in `batch`. Backends that can't do this atomically
+    // just fall back to applying each op in order.
+    fn write(&mut self, batch: WriteBatch) {
+        for op in batch.ops {
+            match op {
+                BatchOp::Insert { key, val } => { self.delete(&key); self.insert(&key, &val); },
+                BatchOp::Delete { key } => { self.delete(&key); },
+            }
+        }
+    }
+    // All live key/value pairs with `start <= key <= end`, in sorted order.
+    fn scan(&self, start: &Data, end: &Data) -> Vec<(Data, Data)>;
 }
 
 #[derive(Debug)]
 struct InMemoryTable {
-    db: BTreeMap<String, String>
+    db: BTreeMap<Data, Data>
 }
 
 impl InMemoryTable {
@@ -107,21 +352,18 @@ impl InMemoryTable {
 }
 
 impl DatabaseTable for InMemoryTable {
-    fn select(&self, key: &str) -> Option<String> {
-        match self.db.get(key) {
-            None => None,
-            Some(x) => Some(x.to_string()),
-        }
+    fn select(&self, key: &Data) -> Option<Data> {
+        self.db.get(key).cloned()
     }
-    fn insert(&mut self, key: &str, val: &str) -> bool {
+    fn insert(&mut self, key: &Data, val: &Data) -> bool {
         if self.db.contains_key(key) {
             return false;
         } else {
-            self.db.insert(key.to_string(), val.to_string());
+            self.db.insert(key.clone(), val.clone());
             return true;
         }
     }
-    fn delete(&mut self, key: &str) -> bool {
+    fn delete(&mut self, key: &Data) -> bool {
         if self.db.contains_key(key) {
             self.db.remove(key);
             return true;
@@ -129,18 +371,109 @@ impl DatabaseTable for InMemoryTable {
             return false;
         }
     }
+    fn scan(&self, start: &Data, end: &Data) -> Vec<(Data, Data)> {
+        self.db.range(start.clone()..=end.clone()).map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
 }
 
 const COMMITLOG: &str = "commitlog";
-const SSTABLE: &str = "sstable";
-const SSTABLE_NEW: &str = "sstable.new";
-const SSTABLE_OLD: &str = "sstable.old";
+// Level 0 is flushed straight from the memtable and its files may overlap
+// in key range, so once there are more than this many we merge everything
+// down into level 1.
+const L0_COMPACTION_TRIGGER: usize = 4;
+// Once the memtable holds more pending mutations than this, it is flushed
+// to a level-0 file automatically, so a long-running process doesn't keep
+// an ever-growing `mutations` map and commitlog in memory/on disk.
+const WRITE_BUFFER_TRIGGER: usize = 1000;
 
 struct DiskTable {
     path: path::PathBuf,
     commitlog: commitlog::CommitLog,
-    mutations: BTreeMap<String, Vec<Command>>,
-    sstable: sstable::Table,
+    mutations: BTreeMap<Data, Vec<(SequenceNumber, Command)>>,
+    // The live file set, across levels, backed by the MANIFEST/CURRENT.
+    versions: VersionSet,
+    // Open handles for every file `versions` currently lists, keyed by
+    // file number.
+    tables: BTreeMap<FileNumber, sstable::Table>,
+    // Bloom filter for each file in `tables`, so a negative lookup never
+    // has to touch disk.
+    blooms: BTreeMap<FileNumber, BloomFilter>,
+    // Next sequence number to assign to a committed mutation.
+    next_seq: SequenceNumber,
+    // Live snapshots, keyed by the sequence they pin (`None` for a
+    // snapshot taken before any write), with a refcount so the same
+    // sequence can be captured by more than one open snapshot.
+    snapshots: BTreeMap<Option<SequenceNumber>, u32>,
+    // The receiving end of a level-0/level-1 merge running on a background
+    // thread, if one is currently in flight - see `maybe_compact`.
+    compaction: Option<std::sync::mpsc::Receiver<CompactionResult>>,
+}
+
+// What a background merge (see `DiskTable::maybe_compact`) reports back:
+// the new level-1 file it built, if the merge wasn't a no-op, plus every
+// file it has made obsolete.
+type CompactionResult = (Option<(FileMetadata, BloomFilter)>, Vec<(usize, FileNumber)>);
+
+// Write `entries` out as a new sstable file (plus its bloom sidecar) named
+// `number`, or None if there is nothing to write. Takes no `&self` so it
+// can run on a background thread against files it alone owns - the
+// caller is responsible for then opening the file and recording it.
+fn build_sstable(
+    path: &path::Path,
+    number: FileNumber,
+    entries: &[(Data, SealedRecord)],
+) -> Option<(FileMetadata, BloomFilter)> {
+    if entries.is_empty() {
+        return None;
+    }
+    let sstable_path = VersionSet::sstable_path(path, number);
+    let mut builder = sstable::TableBuilder::new(sstable::Options::default(), File::create(&sstable_path).expect("Failed to create sstable"));
+    for (key, record) in entries {
+        builder.add(key.as_bytes(), serde_cbor::to_vec(record).expect("Failed to serialize sstable value").as_slice()).expect(format!("Failed to append to sstable. key = {:?}", key).as_str());
+    }
+    builder.finish().expect("Failed to call finish on sstable.");
+    let bloom = BloomFilter::build(entries.iter().map(|(key, _)| key.as_bytes()), bloom::DEFAULT_BITS_PER_KEY);
+    bloom.save(VersionSet::bloom_path(path, number).as_path()).expect("Failed to save bloom filter");
+    let meta = FileMetadata {
+        number,
+        smallest: entries.first().unwrap().0.clone(),
+        largest: entries.last().unwrap().0.clone(),
+    };
+    Some((meta, bloom))
+}
+
+// The actual merge work for `DiskTable::maybe_compact`, run on a
+// background thread: reopen `l0` and `l1`'s files by path (rather than
+// sharing the caller's open `sstable::Table` handles, which we have no
+// reason to assume cross a thread boundary) and merge them newest-first
+// into a single new level-1 file at `number`.
+fn run_compaction(
+    path: &path::Path,
+    l0: &[FileMetadata],
+    l1: &[FileMetadata],
+    number: FileNumber,
+) -> CompactionResult {
+    // l1 first (oldest), then l0 in recency order, so later inserts into
+    // the merge map overwrite older versions of the same key.
+    let mut merged: BTreeMap<Data, SealedRecord> = BTreeMap::new();
+    for file in l1.iter().chain(l0.iter()) {
+        let sstable_path = VersionSet::sstable_path(path, file.number);
+        if let Ok(table) = sstable::Table::new_from_file(sstable::Options::default(), sstable_path.as_path()) {
+            let mut iter = table.iter();
+            while let Some((k, v)) = iter.next() {
+                let key = Data::from_bytes(k);
+                let record: SealedRecord = serde_cbor::from_slice(&v).expect("Failed to decode sstable value");
+                merged.insert(key, record);
+            }
+        }
+    }
+    let entries: Vec<(Data, SealedRecord)> = merged
+        .into_iter()
+        .filter(|(_, record)| !matches!(record.entry, SealedEntry::Tombstone))
+        .collect();
+    let mut remove_files: Vec<(usize, FileNumber)> = l0.iter().map(|f| (0, f.number)).collect();
+    remove_files.extend(l1.iter().map(|f| (1, f.number)));
+    (build_sstable(path, number, &entries), remove_files)
 }
 
 impl DiskTable {
@@ -148,26 +481,73 @@ impl DiskTable {
         if (!path.exists()) {
             std::fs::create_dir(path).expect("Failed to create directory for database");
         }
-        let sstable_path = path.join(SSTABLE);
-        if !sstable_path.exists() {
-            let builder = sstable::TableBuilder::new(sstable::Options::default(), File::create(sstable_path.as_path()).expect("Failed to create empty sstable"));
-            builder.finish().expect("Failed to finalize empty sstable");
+        let versions = VersionSet::open(path);
+        let mut tables: BTreeMap<FileNumber, sstable::Table> = Default::default();
+        let mut blooms: BTreeMap<FileNumber, BloomFilter> = Default::default();
+        for files in &versions.levels {
+            for file in files {
+                let sstable_path = VersionSet::sstable_path(path, file.number);
+                match sstable::Table::new_from_file(sstable::Options::default(), sstable_path.as_path()) {
+                    Ok(table) => {
+                        tables.insert(file.number, table);
+                        if let Ok(bloom) = BloomFilter::load(VersionSet::bloom_path(path, file.number).as_path()) {
+                            blooms.insert(file.number, bloom);
+                        }
+                    },
+                    // A VersionEdit can reference a file that is missing or
+                    // can't be opened on disk. Drop it silently.
+                    Err(_) => {},
+                }
+            }
         }
+        // The durable watermark survives even a compaction that folded every
+        // record out of the commitlog (see `flush_memtable`); the replay
+        // below only ever raises it further, to account for anything
+        // committed since the last such flush.
+        let next_seq = versions.next_seq;
         let mut disktable = DiskTable {
             path: path.to_path_buf(),
             commitlog: CommitLog::new(LogOptions::new(path.join(COMMITLOG))).expect("Failed to open commitlog"),
             mutations: Default::default(),
-            sstable: sstable::Table::new_from_file(sstable::Options::default(), sstable_path.as_path()).expect("Failed to open sstable"),
+            versions,
+            tables,
+            blooms,
+            next_seq,
+            snapshots: Default::default(),
+            compaction: None,
         };
         let messages = disktable.commitlog.read(0, ReadLimit::default()).expect("Failed to read commitlog.");
         for msg in messages.iter() {
-            let cmd: Command = serde_cbor::from_slice(msg.payload()).expect("Failed to deserialize");
-            match cmd.key() {
-                // Fail silently.
-                None => {},
-                Some(key) => {
-                    // Why do we have to copy key here? See https://internals.rust-lang.org/t/pre-rfc-abandonning-morals-in-the-name-of-performance-the-raw-entry-api/7043
-                    disktable.mutations.entry(key.to_string()).or_insert(Default::default()).push(cmd);
+            // A single CBOR payload per message means a batch replays
+            // all-or-nothing: it either deserializes whole, or not at all.
+            let record: Record = serde_cbor::from_slice(msg.payload()).expect("Failed to deserialize");
+            match record {
+                Record::Single { seq, cmd } => {
+                    if seq >= disktable.next_seq {
+                        disktable.next_seq = seq + 1;
+                    }
+                    match cmd.key() {
+                        // Fail silently.
+                        None => {},
+                        Some(key) => {
+                            // Why do we have to copy key here? See https://internals.rust-lang.org/t/pre-rfc-abandonning-morals-in-the-name-of-performance-the-raw-entry-api/7043
+                            disktable.mutations.entry(key.clone()).or_insert(Default::default()).push((seq, cmd));
+                        },
+                    }
+                },
+                Record::Batch { start_seq, ops } => {
+                    let end_seq = start_seq + ops.len() as SequenceNumber;
+                    if end_seq > disktable.next_seq {
+                        disktable.next_seq = end_seq;
+                    }
+                    for (i, op) in ops.into_iter().enumerate() {
+                        let seq = start_seq + i as SequenceNumber;
+                        let (key, cmd) = match op {
+                            BatchOp::Insert { key, val } => (key.clone(), Command::INSERT { key, val }),
+                            BatchOp::Delete { key } => (key.clone(), Command::DELETE(key)),
+                        };
+                        disktable.mutations.entry(key).or_insert(Default::default()).push((seq, cmd));
+                    }
                 },
             }
         }
@@ -176,147 +556,750 @@ impl DiskTable {
     fn dump(&self) {
         println!("{:?}", self.mutations)
     }
-    fn compact_key(&self, key: &str) -> Option<String> {
-        let mut final_val = match self.sstable.get(key.as_bytes()) {
-            Ok(val) => match val {
-                None => None,
-                Some(x) => Some(String::from_utf8(x).expect("Failed to decode UTF-8")),
-            },
-            Err(_) => None,
-        };
-        match self.mutations.get(key) {
-            None => final_val,
-            Some(cmds) => {
-                for cmd in cmds {
-                    match cmd {
-                        Command::INSERT { key: _, val } => {final_val = Some(val.to_string())},
-                        Command::DELETE(_) => {final_val = None},
-                        _ => ()
-                    }
+    // Capture the current set of committed mutations as a stable,
+    // point-in-time view. The snapshot stays valid (see `compaction_floor`)
+    // until released with `release_snapshot`.
+    fn snapshot(&mut self) -> Snapshot {
+        let seq = if self.next_seq == 0 { None } else { Some(self.next_seq - 1) };
+        *self.snapshots.entry(seq).or_insert(0) += 1;
+        Snapshot { seq }
+    }
+    fn release_snapshot(&mut self, snapshot: Snapshot) -> bool {
+        match self.snapshots.get_mut(&snapshot.seq) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.snapshots.remove(&snapshot.seq);
                 }
-                final_val
-            },
+                true
+            }
+            None => false,
         }
     }
-    fn compact(&mut self) {
-        // Algorithm:
-        // * Create a new sstable.
-        // * Iterate over old sstable and mutations, and write new sstable.
-        // * Swap the sstables and delete the old one.
-        // * Truncate the commitlog.
-        let mut builder = sstable::TableBuilder::new(sstable::Options::default(), File::create(self.path.join(SSTABLE_NEW)).expect("Failed to create sstable"));
-        // sstable doesn't implement a standard iteration. grr.
-        let mut sstable_iter = self.sstable.iter();
-        let mut mut_iter = self.mutations.iter();
-        loop {
-            match sstable_iter.next() {
-                // Precondition:
-                None => {
-                    loop {
-                        match mut_iter.next() {
-                            None => break,
-                            Some(x) => {
-                                let mut_key = x.0;
-                                match self.compact_key(mut_key) {
-                                    // We have a commitlog, but the key was ultimately deleted.
-                                    None => (),
-                                    Some(val) => {
-                                        builder.add(mut_key.as_bytes(), val.as_bytes()).expect(format!("Failed to append to sstable. key = {}", mut_key).as_str());
-                                    },
-                                }
-                            },
+    // The oldest sequence still visible to a live snapshot. Compaction must
+    // never fold away a mutation newer than this, or an open snapshot would
+    // see a value it never should have. A snapshot pinned at `None` (taken
+    // before the first write) needs nothing protected - it will never see
+    // any committed value regardless of what compaction does - so only
+    // snapshots pinned at a real sequence constrain the floor. With no such
+    // snapshot live, everything committed so far is safe to fold.
+    fn compaction_floor(&self) -> SequenceNumber {
+        self.snapshots.keys().filter_map(|seq| *seq).min().unwrap_or(SequenceNumber::MAX)
+    }
+    // The on-disk value for `key`, consulting level 0 newest-first and then
+    // each lower level by binary-searching the file whose range covers the
+    // key. A tombstone is a definitive "deleted here" and stops the search,
+    // same as finding a value does.
+    //
+    // A flush only ever folds a key's history down to the single version
+    // newest-or-equal to the floor in effect at the time (see
+    // `fold_mutations`), so each file holds at most one sealed version of
+    // `key` (older files may still hold older versions until a merge). The
+    // newest such version is correct for any `bound` at or after the
+    // floor that was active when it was written - which is guaranteed for
+    // every live snapshot, since `compaction_floor` is the oldest of them.
+    // A `bound` older than that (no snapshot ever pinned it) may find the
+    // on-disk version already moved past it; we skip such a version rather
+    // than returning a value the caller didn't ask for, and keep looking in
+    // older files/levels for one that does satisfy `bound`.
+    fn base_value(&self, key: &Data, bound: Option<SequenceNumber>) -> Option<(SequenceNumber, Data)> {
+        for level in 0..self.versions.levels.len() {
+            for file in self.versions.files_covering(level, key) {
+                if let Some(bloom) = self.blooms.get(&file.number) {
+                    if !bloom.contains(key.as_bytes()) {
+                        continue;
+                    }
+                }
+                if let Some(table) = self.tables.get(&file.number) {
+                    if let Ok(Some(bytes)) = table.get(key.as_bytes()) {
+                        let record: SealedRecord = serde_cbor::from_slice(&bytes).expect("Failed to decode sstable value");
+                        if let Some(bound) = bound {
+                            if record.seq > bound {
+                                continue;
+                            }
                         }
+                        return match record.entry {
+                            SealedEntry::Value(val) => Some((record.seq, val)),
+                            SealedEntry::Tombstone => None,
+                        };
                     }
-                    break
-                },
-                Some(x) => {
-                    let sstable_key = String::from_utf8(x.0).expect("Failed to decode UTF-8");
-                    loop {
-                        match mut_iter.next() {
-                            None => break,
-                            Some(x) => {
-                                match x.0.cmp(&sstable_key) {
-                                    Ordering::Less => {
-                                        let mut_key = x.0;
-                                        match self.compact_key(mut_key) {
-                                            // We have a commitlog, but the key was ultimately deleted.
-                                            None => (),
-                                            Some(val) => {
-                                                builder.add(mut_key.as_bytes(), val.as_bytes()).expect(format!("Failed to append to sstable. key = {}", mut_key).as_str());
-                                            },
-                                        }
-                                    },
-                                    _ => break
-                                }
+                }
+            }
+        }
+        None
+    }
+    // An ordered scan of `start..=end`, merging every live sstable file
+    // with the pending mutations. A key present in more than one source is
+    // resolved with the same precedence as a point lookup
+    // (`compact_key_versioned` over `base_value`): mutations beat
+    // any sstable, and within the sstables level 0 newest-first beats the
+    // rest.
+    fn db_iter(&self, start: &Data, end: &Data) -> DBIterator {
+        self.db_iter_bounded(Some(start), Some(end))
+    }
+    // Every live key/value pair, merged the same way as `db_iter` but with
+    // no bound on key range - see `StorageBackend::scan_all`.
+    fn db_iter_all(&self) -> DBIterator {
+        self.db_iter_bounded(None, None)
+    }
+    fn db_iter_bounded(&self, start: Option<&Data>, end: Option<&Data>) -> DBIterator {
+        let in_range = |key: &Data| {
+            start.map_or(true, |s| key >= s) && end.map_or(true, |e| key <= e)
+        };
+        let mutation_range: Box<dyn Iterator<Item = (&Data, &Vec<(SequenceNumber, Command)>)>> = match (start, end) {
+            (Some(s), Some(e)) => Box::new(self.mutations.range(s.clone()..=e.clone())),
+            _ => Box::new(self.mutations.iter()),
+        };
+        let mut_entries: Vec<(Data, SealedEntry)> = mutation_range
+            .filter_map(|(key, cmds)| {
+                let mut outcome: Option<SealedEntry> = None;
+                for (_, cmd) in cmds {
+                    match cmd {
+                        Command::INSERT { key: _, val } => { outcome = Some(SealedEntry::Value(val.clone())) },
+                        Command::DELETE(_) => { outcome = Some(SealedEntry::Tombstone) },
+                        _ => (),
+                    }
+                }
+                outcome.map(|entry| (key.clone(), entry))
+            })
+            .collect();
+        let mut sources: Vec<Vec<(Data, SealedEntry)>> = vec![mut_entries];
+        for level in 0..self.versions.levels.len() {
+            let files: Vec<&FileMetadata> = if level == 0 {
+                self.versions.levels[0].iter().rev().collect()
+            } else {
+                self.versions.levels[level].iter().collect()
+            };
+            for file in files {
+                if start.map_or(false, |s| file.largest < *s) || end.map_or(false, |e| file.smallest > *e) {
+                    continue;
+                }
+                if let Some(table) = self.tables.get(&file.number) {
+                    let mut entries = Vec::new();
+                    let mut iter = table.iter();
+                    while let Some((k, v)) = iter.next() {
+                        let key = Data::from_bytes(k);
+                        if !in_range(&key) {
+                            if end.map_or(false, |e| key > *e) {
+                                break;
                             }
+                            continue;
                         }
+                        let record: SealedRecord = serde_cbor::from_slice(&v).expect("Failed to decode sstable value");
+                        entries.push((key, record.entry));
                     }
-                    builder.add(sstable_key.as_bytes(), self.compact_key(sstable_key.as_str()).expect("Failed to get key").as_bytes()).expect("Failed to append to sstable");
-                },
+                    sources.push(entries);
+                }
             }
         }
-        builder.finish().expect("Failed to call finish on sstable.");
-        std::fs::rename(self.path.join(SSTABLE), self.path.join(SSTABLE_OLD)).expect("Failed to rename cur to old");
-        std::fs::rename(self.path.join(SSTABLE_NEW), self.path.join(SSTABLE)).expect("Failed to rename new to cur");
-        std::fs::remove_file(self.path.join(SSTABLE_OLD)).expect("Failed to remove old sstable");
-        self.sstable = sstable::Table::new_from_file(sstable::Options::default(), self.path.join(SSTABLE).as_path()).expect("Failed to open sstable");
+        DBIterator { entries: merge_sources(sources).into_iter() }
+    }
+    // Resolve `key` as of `bound` (or the latest committed value, if
+    // `bound` is None), returning the value along with the sequence that
+    // committed it.
+    fn compact_key_versioned(&self, key: &Data, bound: Option<SequenceNumber>) -> Option<(SequenceNumber, Data)> {
+        let mut final_val = self.base_value(key, bound);
+        if let Some(cmds) = self.mutations.get(key) {
+            for (seq, cmd) in cmds {
+                if let Some(bound) = bound {
+                    if *seq > bound {
+                        continue;
+                    }
+                }
+                match cmd {
+                    Command::INSERT { key: _, val } => { final_val = Some((*seq, val.clone())) },
+                    Command::DELETE(_) => { final_val = None },
+                    _ => ()
+                }
+            }
+        }
+        final_val
+    }
+    fn compact_key(&self, key: &Data, bound: Option<SequenceNumber>) -> Option<Data> {
+        self.compact_key_versioned(key, bound).map(|(_, val)| val)
+    }
+    // Fold just the pending mutations for `key` (ignoring whatever base
+    // value is already durable on disk) into the single entry a memtable
+    // flush should write for it, or None if nothing for this key is at or
+    // below `bound` yet.
+    fn fold_mutations(&self, key: &Data, bound: SequenceNumber) -> Option<SealedRecord> {
+        let cmds = self.mutations.get(key)?;
+        let mut result: Option<SealedRecord> = None;
+        for (seq, cmd) in cmds {
+            if *seq > bound {
+                continue;
+            }
+            match cmd {
+                Command::INSERT { key: _, val } => { result = Some(SealedRecord { seq: *seq, entry: SealedEntry::Value(val.clone()) }) },
+                Command::DELETE(_) => { result = Some(SealedRecord { seq: *seq, entry: SealedEntry::Tombstone }) },
+                _ => (),
+            }
+        }
+        result
+    }
+    fn write_sstable(&mut self, entries: &[(Data, SealedRecord)]) -> Option<FileMetadata> {
+        if entries.is_empty() {
+            return None;
+        }
+        let number = self.versions.new_file_number();
+        let (meta, bloom) = build_sstable(&self.path, number, entries)?;
+        let sstable_path = VersionSet::sstable_path(&self.path, number);
+        let table = sstable::Table::new_from_file(sstable::Options::default(), sstable_path.as_path()).expect("Failed to open freshly written sstable");
+        self.tables.insert(number, table);
+        self.blooms.insert(number, bloom);
+        Some(meta)
+    }
+    // Flush the memtable into a new level-0 file, then rewrite the
+    // commitlog to hold only whatever is still above the compaction floor
+    // (i.e. not yet safe to fold away because an open snapshot needs it).
+    fn flush_memtable(&mut self) {
+        let floor = self.compaction_floor();
+        let keys: Vec<Data> = self.mutations.keys().cloned().collect();
+        let mut entries: Vec<(Data, SealedRecord)> = Vec::new();
+        for key in keys {
+            if let Some(record) = self.fold_mutations(&key, floor) {
+                entries.push((key, record));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let add_files = match self.write_sstable(&entries) {
+            Some(meta) => vec![(0, meta)],
+            None => vec![],
+        };
+        // With no live snapshot, `floor` is SequenceNumber::MAX, so every
+        // mutation folds into the sstable (a delete as a tombstone entry)
+        // and the commitlog rewrite below can
+        // leave nothing behind at all. next_seq must still survive a
+        // restart in that case, so log it here unconditionally rather than
+        // only when there happened to be a file to add - see
+        // `DiskTable::new`.
+        self.versions.log_and_apply(VersionEdit {
+            next_file_number: None,
+            next_seq: Some(self.next_seq),
+            add_files,
+            remove_files: vec![],
+        });
+        let mut retained: Vec<(SequenceNumber, Command)> = Vec::new();
+        self.mutations.retain(|_, cmds| {
+            cmds.retain(|(seq, _)| *seq > floor);
+            !cmds.is_empty()
+        });
+        for cmds in self.mutations.values() {
+            retained.extend(cmds.iter().cloned());
+        }
+        retained.sort_by_key(|(seq, _)| *seq);
         // Note: self.commitlog.truncate(0) doesn't work. It leaves one element in the commitlog.
         std::fs::remove_dir_all(self.path.join(COMMITLOG)).expect("Failed to remove commitlog");
         self.commitlog = CommitLog::new(LogOptions::new(self.path.join(COMMITLOG))).expect("Failed to open commitlog");
-        self.mutations.clear();
+        for (seq, cmd) in retained {
+            let record = Record::Single { seq, cmd };
+            self.commitlog.append_msg(serde_cbor::to_vec(&record).expect("Failed to serialize")).expect("Failed to append to commitlog");
+        }
+        self.commitlog.flush().expect("failed to flush commitlog");
+    }
+    // Apply a background merge's result, if one has finished (`block`
+    // false: only if it's ready right now; `block` true: wait for it). This
+    // is the only place that touches `self.tables`/`self.blooms`/
+    // `self.versions` on its behalf, so there is no state shared with the
+    // worker thread to synchronize - it only ever reports back over `tx`.
+    fn poll_compaction(&mut self, block: bool) {
+        let result = match &self.compaction {
+            None => return,
+            Some(rx) => if block { rx.recv().ok() } else { rx.try_recv().ok() },
+        };
+        let (built, remove_files) = match result {
+            Some(result) => result,
+            None => return,
+        };
+        self.compaction = None;
+        let mut add_files = Vec::new();
+        if let Some((meta, bloom)) = built {
+            let sstable_path = VersionSet::sstable_path(&self.path, meta.number);
+            let table = sstable::Table::new_from_file(sstable::Options::default(), sstable_path.as_path()).expect("Failed to open freshly written sstable");
+            self.tables.insert(meta.number, table);
+            self.blooms.insert(meta.number, bloom);
+            add_files.push((1, meta));
+        }
+        self.versions.log_and_apply(VersionEdit { next_file_number: None, next_seq: None, add_files, remove_files: remove_files.clone() });
+        for (_, number) in remove_files {
+            self.tables.remove(&number);
+            self.blooms.remove(&number);
+            let _ = std::fs::remove_file(VersionSet::sstable_path(&self.path, number));
+            let _ = std::fs::remove_file(VersionSet::bloom_path(&self.path, number));
+        }
+    }
+    // Once level 0 has piled up past the trigger, spawn a background thread
+    // to merge every level-0 file with level 1 (newest write wins) into a
+    // single new level-1 file, dropping tombstones now that there is
+    // nothing lower left to shadow. Only one merge runs at a time; this is
+    // a no-op if one is already in flight, until `poll_compaction` applies
+    // it.
+    fn maybe_compact(&mut self) {
+        self.poll_compaction(false);
+        if self.compaction.is_some() {
+            return;
+        }
+        let l0_count = self.versions.levels.get(0).map_or(0, |f| f.len());
+        if l0_count <= L0_COMPACTION_TRIGGER {
+            return;
+        }
+        let l0 = self.versions.levels[0].clone();
+        let l1 = self.versions.levels.get(1).cloned().unwrap_or_default();
+        let number = self.versions.new_file_number();
+        let path = self.path.clone();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            // The receiver may already be gone if the DiskTable was
+            // dropped before this finished; nothing to do in that case.
+            let _ = tx.send(run_compaction(&path, &l0, &l1, number));
+        });
+        self.compaction = Some(rx);
+    }
+    // Flush the memtable and start a background merge if level 0 has
+    // piled up, without waiting for that merge to finish - see
+    // `maybe_flush`. An explicit `.compact` additionally waits for it (see
+    // `run_repl_disk`), since a user who asks to compact wants to know it
+    // actually happened before the command returns.
+    fn compact(&mut self) {
+        self.flush_memtable();
+        self.maybe_compact();
+    }
+    // Total mutations still sitting in the memtable, across all keys.
+    fn pending_mutation_count(&self) -> usize {
+        self.mutations.values().map(|cmds| cmds.len()).sum()
+    }
+    // Called after every write; once the memtable has grown past
+    // `WRITE_BUFFER_TRIGGER`, flush it to a new level-0 file so memory and
+    // commitlog size stay bounded without the user ever having to type
+    // `.compact`. The flush itself (a single sstable write) runs inline,
+    // but the potentially much larger level-0/level-1 merge it can trigger
+    // runs on a background thread (see `maybe_compact`), so a write that
+    // crosses the threshold doesn't pay for a full merge itself.
+    fn maybe_flush(&mut self) {
+        if self.pending_mutation_count() > WRITE_BUFFER_TRIGGER {
+            self.compact();
+        }
+    }
+}
+
+// One source's current front entry in the `merge_sources` heap. `source`
+// both identifies which iterator to advance and breaks ties between
+// sources that both hold `key`: source 0 is the mutations source, and
+// higher indices are sstable sources in the same newest-first,
+// level-by-level precedence `base_value` probes them in, so the winner
+// always matches what a point lookup would have returned.
+struct HeapItem {
+    key: Data,
+    entry: SealedEntry,
+    source: usize,
+}
+
+impl PartialEq for HeapItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key && self.source == other.source
+    }
+}
+impl Eq for HeapItem {}
+impl Ord for HeapItem {
+    // Reversed so `BinaryHeap` (a max-heap) pops the smallest key first,
+    // and among equal keys the lowest source index (highest precedence)
+    // first.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.key.cmp(&self.key).then_with(|| other.source.cmp(&self.source))
+    }
+}
+impl PartialOrd for HeapItem {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// A k-way merge of already-sorted `(key, entry)` sources, in precedence
+// order (`sources[0]` is the highest-precedence, i.e. mutations). Ties on
+// the same key are resolved in favor of the lowest-index source, and a
+// tombstone winner is suppressed from the output rather than emitted.
+fn merge_sources(sources: Vec<Vec<(Data, SealedEntry)>>) -> Vec<(Data, Data)> {
+    let mut iters: Vec<std::vec::IntoIter<(Data, SealedEntry)>> =
+        sources.into_iter().map(|s| s.into_iter()).collect();
+    let mut heap: std::collections::BinaryHeap<HeapItem> = std::collections::BinaryHeap::new();
+    for (source, iter) in iters.iter_mut().enumerate() {
+        if let Some((key, entry)) = iter.next() {
+            heap.push(HeapItem { key, entry, source });
+        }
+    }
+    let mut result = Vec::new();
+    while let Some(mut winner) = heap.pop() {
+        let key = winner.key.clone();
+        while let Some(next) = heap.peek() {
+            if next.key != key {
+                break;
+            }
+            let next = heap.pop().unwrap();
+            if let Some((k, e)) = iters[next.source].next() {
+                heap.push(HeapItem { key: k, entry: e, source: next.source });
+            }
+        }
+        if let Some((k, e)) = iters[winner.source].next() {
+            heap.push(HeapItem { key: k, entry: e, source: winner.source });
+        }
+        if let SealedEntry::Value(val) = std::mem::replace(&mut winner.entry, SealedEntry::Tombstone) {
+            result.push((key, val));
+        }
+    }
+    result
+}
+
+// The result of `DiskTable::db_iter`, yielding merged `(key, value)` pairs
+// in sorted order.
+struct DBIterator {
+    entries: std::vec::IntoIter<(Data, Data)>,
+}
+
+impl Iterator for DBIterator {
+    type Item = (Data, Data);
+    fn next(&mut self) -> Option<(Data, Data)> {
+        self.entries.next()
     }
 }
 
 impl DatabaseTable for DiskTable {
-    fn insert(&mut self, key: &str, val: &str) -> bool {
-        match self.compact_key(key) {
+    fn insert(&mut self, key: &Data, val: &Data) -> bool {
+        match self.compact_key(key, None) {
             Some(_) => false,
             None => {
-                let cmd = Command::INSERT {key:key.to_string(), val: val.to_string()};
-                self.commitlog.append_msg(serde_cbor::to_vec(&cmd).expect("Failed to serialize")).expect("Failed to append to commitlog");
-                self.mutations.entry(key.to_string()).or_insert(Default::default()).push(cmd);
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                let cmd = Command::INSERT { key: key.clone(), val: val.clone() };
+                let record = Record::Single { seq, cmd: cmd.clone() };
+                self.commitlog.append_msg(serde_cbor::to_vec(&record).expect("Failed to serialize")).expect("Failed to append to commitlog");
+                self.mutations.entry(key.clone()).or_insert(Default::default()).push((seq, cmd));
                 self.commitlog.flush().expect("failed to flush commitlog");
+                self.maybe_flush();
                 true
             }
         }
     }
 
-    fn select(&self, key: &str) -> Option<String> {
-        self.compact_key(key)
+    fn select(&self, key: &Data) -> Option<Data> {
+        self.compact_key(key, None)
+    }
+
+    fn select_as_of(&self, key: &Data, seq: SequenceNumber) -> Option<Data> {
+        self.compact_key(key, Some(seq))
     }
 
-    fn delete(&mut self, key: &str) -> bool {
-        match self.compact_key(key) {
+    fn delete(&mut self, key: &Data) -> bool {
+        match self.compact_key(key, None) {
             Some(_) => {
-                let cmd = Command::DELETE(key.to_string());
-                self.commitlog.append_msg(serde_cbor::to_vec(&cmd).expect("Failed to serialize")).expect("Failed to append to commitlog");
-                self.mutations.entry(key.to_string()).or_insert(Default::default()).push(cmd);
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                let cmd = Command::DELETE(key.clone());
+                let record = Record::Single { seq, cmd: cmd.clone() };
+                self.commitlog.append_msg(serde_cbor::to_vec(&record).expect("Failed to serialize")).expect("Failed to append to commitlog");
+                self.mutations.entry(key.clone()).or_insert(Default::default()).push((seq, cmd));
                 self.commitlog.flush().expect("failed to flush commitlog");
+                self.maybe_flush();
                 true
             },
             None => false
         }
     }
+
+    // Unlike standalone `insert`, a batched `Insert` always wins: it is not
+    // checked against an existing key and will silently overwrite one, the
+    // same as LevelDB's `WriteBatch::Put`. A batch is an explicit list of
+    // operations the caller already chose, so there is no single natural
+    // "duplicate key" to refuse the way there is for a lone `insert` call.
+    fn write(&mut self, batch: WriteBatch) {
+        if batch.ops.is_empty() {
+            return;
+        }
+        let start_seq = self.next_seq;
+        self.next_seq += batch.ops.len() as SequenceNumber;
+        let record = Record::Batch { start_seq, ops: batch.ops.clone() };
+        self.commitlog.append_msg(serde_cbor::to_vec(&record).expect("Failed to serialize")).expect("Failed to append to commitlog");
+        self.commitlog.flush().expect("failed to flush commitlog");
+        for (i, op) in batch.ops.into_iter().enumerate() {
+            let seq = start_seq + i as SequenceNumber;
+            let (key, cmd) = match op {
+                BatchOp::Insert { key, val } => (key.clone(), Command::INSERT { key, val }),
+                BatchOp::Delete { key } => (key.clone(), Command::DELETE(key)),
+            };
+            self.mutations.entry(key).or_insert(Default::default()).push((seq, cmd));
+        }
+        self.maybe_flush();
+    }
+
+    fn scan(&self, start: &Data, end: &Data) -> Vec<(Data, Data)> {
+        self.db_iter(start, end).collect()
+    }
 }
 
-fn main() {
+impl StorageBackend for DiskTable {
+    fn open(path: &path::Path) -> DiskTable {
+        DiskTable::new(path)
+    }
+    fn get(&self, key: &Data) -> Option<Data> {
+        self.select(key)
+    }
+    // `DatabaseTable::insert` refuses to overwrite an existing key, but a
+    // generic storage backend just needs a plain upsert, so delete any
+    // prior value first.
+    fn put(&mut self, key: &Data, val: &Data) {
+        if self.select(key).is_some() {
+            DatabaseTable::delete(self, key);
+        }
+        self.insert(key, val);
+    }
+    fn delete(&mut self, key: &Data) -> bool {
+        DatabaseTable::delete(self, key)
+    }
+    fn scan(&self, start: &Data, end: &Data) -> Vec<(Data, Data)> {
+        DatabaseTable::scan(self, start, end)
+    }
+    fn scan_all(&self) -> Vec<(Data, Data)> {
+        self.db_iter_all().collect()
+    }
+    fn flush(&mut self) {
+        self.compact();
+        // Unlike the auto-triggered path in `maybe_flush`, a caller of the
+        // generic `StorageBackend` interface (export/import/convert) needs
+        // every pending merge actually done before this returns.
+        self.poll_compaction(true);
+    }
+}
+
+// Stream every live key/value in `backend` out to `dump_path` as a single
+// CBOR-encoded `Vec<(Data, Data)>` - a portable format any backend can
+// read back in with `import_dump`, regardless of its own on-disk layout.
+fn export_dump(backend: &dyn StorageBackend, dump_path: &path::Path) {
+    let entries = backend.scan_all();
+    let mut f = File::create(dump_path).expect("Failed to create dump file");
+    f.write_all(&serde_cbor::to_vec(&entries).expect("Failed to serialize dump")).expect("Failed to write dump file");
+}
+
+// Load a dump written by `export_dump` into `backend`, then flush it.
+fn import_dump(backend: &mut dyn StorageBackend, dump_path: &path::Path) {
+    let mut f = File::open(dump_path).expect("Failed to open dump file");
+    let mut buf = Vec::new();
+    f.read_to_end(&mut buf).expect("Failed to read dump file");
+    let entries: Vec<(Data, Data)> = serde_cbor::from_slice(&buf).expect("Failed to deserialize dump");
+    for (key, val) in entries {
+        backend.put(&key, &val);
+    }
+    backend.flush();
+}
+
+fn open_backend(kind: &str, path: &path::Path) -> Box<dyn StorageBackend> {
+    match kind {
+        "file" => Box::new(FileTable::open(path)),
+        _ => Box::new(DiskTable::open(path)),
+    }
+}
+
+// Read every live key/value out of `from_path` (engine `from_kind`) and
+// write it into `to_path` (engine `to_kind`), migrating between storage
+// backends or between versions of the same one.
+fn convert_backend(from_kind: &str, from_path: &path::Path, to_kind: &str, to_path: &path::Path) {
+    let source = open_backend(from_kind, from_path);
+    let entries = source.scan_all();
+    let mut dest = open_backend(to_kind, to_path);
+    for (key, val) in entries {
+        dest.put(&key, &val);
+    }
+    dest.flush();
+}
+
+fn run_cli(args: &[String]) {
+    match args {
+        [cmd, from_kind, from_path, to_kind, to_path] if cmd == "convert" => {
+            convert_backend(from_kind, path::Path::new(from_path), to_kind, path::Path::new(to_path));
+        }
+        _ => println!("usage: convert <from-backend> <from-path> <to-backend> <to-path>"),
+    }
+}
+
+// `--backend <kind>` (default "disk") and `--path <path>` (default
+// "foobar") for starting the interactive REPL against a chosen backend -
+// previously only `convert` could ever open a `FileTable`.
+fn parse_repl_args(args: &[String]) -> (String, path::PathBuf) {
+    let mut kind = "disk".to_string();
+    let mut db_path = path::PathBuf::from("foobar");
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--backend" => if let Some(v) = iter.next() { kind = v.clone(); },
+            "--path" => if let Some(v) = iter.next() { db_path = path::PathBuf::from(v); },
+            _ => {}
+        }
+    }
+    (kind, db_path)
+}
+
+// The REPL loop for `DiskTable`, which on top of `StorageBackend` also
+// supports as-of reads, atomic batches and explicit snapshots/compaction.
+fn run_repl_disk(db: &mut DiskTable) {
     println!("Enter a command");
-    let mut db = DiskTable::new(path::Path::new("foobar"));
     loop {
         let mut line = String::new();
         io::stdin().read_line(&mut line).expect("Failed to read line");
-//        println!("Your command was: {}", line);
         match Command::parse(&line) {
             None => { println!("Unrecognized command. Use .exit to quit.") }
             Some(cmd) => {
                 println!("{:?}", cmd);
                 match cmd {
-                    Command::COMPACT => db.compact(),
+                    Command::COMPACT => {
+                        db.compact();
+                        // A user who explicitly asks to compact wants to
+                        // know it actually finished, unlike the background
+                        // merge an ordinary write can trigger.
+                        db.poll_compaction(true);
+                    }
                     Command::DUMP => db.dump(),
+                    Command::EXPORT(dump_path) => export_dump(db, path::Path::new(&dump_path)),
+                    Command::IMPORT(dump_path) => import_dump(db, path::Path::new(&dump_path)),
+                    Command::SNAPSHOT => {
+                        let s = db.snapshot();
+                        match s.sequence() {
+                            Some(seq) => println!("Snapshot {}", seq),
+                            None => println!("Snapshot none"),
+                        }
+                    }
+                    Command::RELEASE(seq) => {
+                        if db.release_snapshot(Snapshot { seq }) {
+                            match seq {
+                                Some(seq) => println!("Released {}", seq),
+                                None => println!("Released none"),
+                            }
+                        } else {
+                            println!("No such snapshot")
+                        }
+                    }
                     Command::EXIT => break,
-                    _ => cmd.execute(&mut db)
+                    _ => cmd.execute(db)
                 }
             }
         }
     }
 }
+
+// The REPL loop for any other `StorageBackend`, restricted to what that
+// trait can express: no as-of reads, no atomic batches, no snapshots.
+fn run_repl_backend(db: &mut dyn StorageBackend) {
+    println!("Enter a command");
+    loop {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).expect("Failed to read line");
+        match Command::parse(&line) {
+            None => { println!("Unrecognized command. Use .exit to quit.") }
+            Some(cmd) => {
+                println!("{:?}", cmd);
+                match cmd {
+                    Command::COMPACT => db.flush(),
+                    Command::DUMP => println!("{:?}", db.scan_all()),
+                    Command::EXPORT(dump_path) => export_dump(db, path::Path::new(&dump_path)),
+                    Command::IMPORT(dump_path) => import_dump(db, path::Path::new(&dump_path)),
+                    Command::EXIT => break,
+                    Command::SELECT { key: _, as_of: Some(_) } => println!("as-of reads are not supported on this backend"),
+                    Command::SELECT { key, as_of: None } => match db.get(&key) {
+                        None => println!("Not found"),
+                        Some(val) => println!("{}", val.display()),
+                    },
+                    Command::INSERT { key, val } => {
+                        if db.get(&key).is_some() {
+                            println!("Duplicate key")
+                        } else {
+                            db.put(&key, &val);
+                            println!("Succeeded")
+                        }
+                    }
+                    Command::DELETE(key) => {
+                        if db.delete(&key) { println!("Succeeded") } else { println!("Not found") }
+                    }
+                    Command::BATCH(batch) => {
+                        for op in batch.ops {
+                            match op {
+                                BatchOp::Insert { key, val } => db.put(&key, &val),
+                                BatchOp::Delete { key } => { db.delete(&key); },
+                            }
+                        }
+                        println!("Succeeded")
+                    }
+                    Command::SCAN { start, end } => {
+                        for (key, val) in db.scan(&start, &end) {
+                            println!("{} = {}", key.display(), val.display())
+                        }
+                    }
+                    Command::SNAPSHOT | Command::RELEASE(_) => println!("snapshots are not supported on this backend"),
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let cli_args: Vec<String> = std::env::args().collect();
+    if cli_args.len() > 1 && cli_args[1] == "convert" {
+        run_cli(&cli_args[1..]);
+        return;
+    }
+    let (kind, db_path) = parse_repl_args(&cli_args[1..]);
+    match kind.as_str() {
+        "file" => run_repl_backend(&mut FileTable::open(&db_path)),
+        _ => run_repl_disk(&mut DiskTable::new(&db_path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(s: &str) -> Data {
+        Data::from_bytes(s.as_bytes().to_vec())
+    }
+    fn value(s: &str) -> SealedEntry {
+        SealedEntry::Value(data(s))
+    }
+
+    #[test]
+    fn merges_disjoint_sources_in_key_order() {
+        let a = vec![(data("a"), value("1")), (data("c"), value("3"))];
+        let b = vec![(data("b"), value("2"))];
+        let merged = merge_sources(vec![a, b]);
+        assert_eq!(
+            merged,
+            vec![(data("a"), data("1")), (data("b"), data("2")), (data("c"), data("3"))]
+        );
+    }
+
+    #[test]
+    fn lower_source_index_wins_on_a_shared_key() {
+        // Source 0 is mutations (highest precedence), matching a point
+        // lookup: mutations, then sstables newest-first (`base_value`).
+        let mutations = vec![(data("k"), value("new"))];
+        let sstable = vec![(data("k"), value("old"))];
+        let merged = merge_sources(vec![mutations, sstable]);
+        assert_eq!(merged, vec![(data("k"), data("new"))]);
+    }
+
+    #[test]
+    fn a_tombstone_winner_suppresses_the_key_entirely() {
+        let mutations = vec![(data("k"), SealedEntry::Tombstone)];
+        let sstable = vec![(data("k"), value("old"))];
+        let merged = merge_sources(vec![mutations, sstable]);
+        assert_eq!(merged, Vec::new());
+    }
+
+    #[test]
+    fn a_tombstone_losing_a_tie_does_not_suppress_the_winner() {
+        // A tombstone in the higher-precedence source shadows the value
+        // behind it - covered separately above. This checks the reverse
+        // pairing: a
+        // value ahead of a tombstone at a lower-precedence source still
+        // wins.
+        let mutations = vec![(data("k"), value("new"))];
+        let sstable = vec![(data("k"), SealedEntry::Tombstone)];
+        let merged = merge_sources(vec![mutations, sstable]);
+        assert_eq!(merged, vec![(data("k"), data("new"))]);
+    }
+}
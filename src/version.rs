@@ -0,0 +1,332 @@
+// Tracks the live set of sstable files across levels.
+//
+// Structural changes (a flush adding a level-0 file, a compaction replacing
+// files with a merged one) are persisted as append-only `VersionEdit`
+// records in a MANIFEST log before they take effect in memory, and a
+// `CURRENT` file (written via a temp-file-then-rename so it is never
+// observed half-written) records which manifest generation is active.
+// Recovery just replays the manifest from scratch to reconstruct the live
+// file set; a file with no corresponding `VersionEdit` is an orphan from a
+// crash between writing the sstable and logging the edit, and is safely
+// ignored. This is LevelDB's VersionSet/MANIFEST/CURRENT scheme.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use commitlog::message::MessageSet;
+use commitlog::*;
+
+use super::data::Data;
+
+pub type FileNumber = u64;
+
+const CURRENT: &str = "CURRENT";
+const MANIFEST_PREFIX: &str = "MANIFEST";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileMetadata {
+    pub number: FileNumber,
+    pub smallest: Data,
+    pub largest: Data,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VersionEdit {
+    pub next_file_number: Option<FileNumber>,
+    // The caller's own mutation-sequence watermark as of this edit, opaque
+    // to `VersionSet` itself - see `VersionSet::next_seq`.
+    pub next_seq: Option<u64>,
+    // (level, file) pairs added or removed by this edit.
+    pub add_files: Vec<(usize, FileMetadata)>,
+    pub remove_files: Vec<(usize, FileNumber)>,
+}
+
+pub struct VersionSet {
+    manifest: CommitLog,
+    pub levels: Vec<Vec<FileMetadata>>,
+    pub next_file_number: FileNumber,
+    // The highest mutation-sequence watermark any caller has logged via
+    // `log_and_apply`. `VersionSet` doesn't assign or interpret this value
+    // itself - it just durably remembers it, the same way it remembers
+    // `next_file_number`, so a caller whose own source of truth can be
+    // truncated (DiskTable's commitlog, once a compaction folds everything
+    // in it away) still has a durable floor to recover from.
+    pub next_seq: u64,
+}
+
+impl VersionSet {
+    pub fn sstable_path(path: &Path, number: FileNumber) -> PathBuf {
+        path.join(format!("sstable-{:06}", number))
+    }
+
+    pub fn bloom_path(path: &Path, number: FileNumber) -> PathBuf {
+        path.join(format!("sstable-{:06}.bloom", number))
+    }
+
+    fn manifest_dir(path: &Path, generation: u64) -> PathBuf {
+        path.join(format!("{}-{:06}", MANIFEST_PREFIX, generation))
+    }
+
+    fn write_current(path: &Path, generation: u64) {
+        let tmp_path = path.join("CURRENT.tmp");
+        let mut f = File::create(&tmp_path).expect("Failed to create CURRENT.tmp");
+        write!(f, "{}-{:06}", MANIFEST_PREFIX, generation).expect("Failed to write CURRENT.tmp");
+        f.flush().expect("Failed to flush CURRENT.tmp");
+        std::fs::rename(&tmp_path, path.join(CURRENT)).expect("Failed to install CURRENT");
+    }
+
+    pub fn open(path: &Path) -> VersionSet {
+        let current_path = path.join(CURRENT);
+        let generation: u64 = if current_path.exists() {
+            std::fs::read_to_string(&current_path)
+                .expect("Failed to read CURRENT")
+                .trim()
+                .trim_start_matches(&format!("{}-", MANIFEST_PREFIX))
+                .parse()
+                .expect("Malformed CURRENT file")
+        } else {
+            0
+        };
+        let manifest = CommitLog::new(LogOptions::new(Self::manifest_dir(path, generation)))
+            .expect("Failed to open manifest");
+        let mut levels: Vec<Vec<FileMetadata>> = Vec::new();
+        let mut next_file_number: FileNumber = 1;
+        let mut next_seq: u64 = 0;
+        let messages = manifest
+            .read(0, ReadLimit::default())
+            .expect("Failed to read manifest");
+        for msg in messages.iter() {
+            let edit: VersionEdit =
+                serde_cbor::from_slice(msg.payload()).expect("Failed to deserialize VersionEdit");
+            apply_edit(&mut levels, &mut next_file_number, &mut next_seq, &edit);
+        }
+        if !current_path.exists() {
+            Self::write_current(path, generation);
+        }
+        VersionSet { manifest, levels, next_file_number, next_seq }
+    }
+
+    pub fn new_file_number(&mut self) -> FileNumber {
+        let n = self.next_file_number;
+        self.next_file_number += 1;
+        n
+    }
+
+    // Durably record `edit`, then apply it to the in-memory level state.
+    // `edit.next_seq`, unlike `next_file_number`, is left as the caller set
+    // it: it's the caller's own counter, not something `VersionSet` can
+    // derive on their behalf.
+    pub fn log_and_apply(&mut self, mut edit: VersionEdit) {
+        edit.next_file_number = Some(self.next_file_number);
+        self.manifest
+            .append_msg(serde_cbor::to_vec(&edit).expect("Failed to serialize VersionEdit"))
+            .expect("Failed to append VersionEdit");
+        self.manifest.flush().expect("Failed to flush manifest");
+        apply_edit(&mut self.levels, &mut self.next_file_number, &mut self.next_seq, &edit);
+    }
+
+    // Files in `level` whose range could contain `key`, ordered so the
+    // caller can stop at the first hit: newest-first for level 0, where
+    // files may overlap, and the single covering file otherwise, since
+    // levels above 0 are kept range-partitioned.
+    pub fn files_covering(&self, level: usize, key: &Data) -> Vec<&FileMetadata> {
+        let files = match self.levels.get(level) {
+            None => return Vec::new(),
+            Some(files) => files,
+        };
+        if level == 0 {
+            files
+                .iter()
+                .rev()
+                .filter(|f| *key >= f.smallest && *key <= f.largest)
+                .collect()
+        } else {
+            match files.binary_search_by(|f| {
+                if *key < f.smallest {
+                    Ordering::Greater
+                } else if *key > f.largest {
+                    Ordering::Less
+                } else {
+                    Ordering::Equal
+                }
+            }) {
+                Ok(idx) => vec![&files[idx]],
+                Err(_) => Vec::new(),
+            }
+        }
+    }
+}
+
+fn apply_edit(
+    levels: &mut Vec<Vec<FileMetadata>>,
+    next_file_number: &mut FileNumber,
+    next_seq: &mut u64,
+    edit: &VersionEdit,
+) {
+    for (level, number) in &edit.remove_files {
+        if let Some(files) = levels.get_mut(*level) {
+            files.retain(|f| f.number != *number);
+        }
+    }
+    for (level, meta) in &edit.add_files {
+        while levels.len() <= *level {
+            levels.push(Vec::new());
+        }
+        // The file itself is the source of truth for which numbers are
+        // taken: derive from it directly rather than trusting a caller to
+        // have reserved it via `new_file_number` first, so this holds by
+        // construction instead of by caller convention.
+        if meta.number >= *next_file_number {
+            *next_file_number = meta.number + 1;
+        }
+        levels[*level].push(meta.clone());
+        if *level > 0 {
+            // Levels above 0 are range-partitioned, so keep them sorted
+            // for the binary search in `files_covering`.
+            levels[*level].sort_by(|a, b| a.smallest.cmp(&b.smallest));
+        }
+    }
+    if let Some(n) = edit.next_file_number {
+        if n > *next_file_number {
+            *next_file_number = n;
+        }
+    }
+    if let Some(seq) = edit.next_seq {
+        if seq > *next_seq {
+            *next_seq = seq;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(number: FileNumber, smallest: &[u8], largest: &[u8]) -> FileMetadata {
+        FileMetadata {
+            number,
+            smallest: Data::from_bytes(smallest.to_vec()),
+            largest: Data::from_bytes(largest.to_vec()),
+        }
+    }
+
+    // A fresh VersionSet replayed from an empty directory starts empty, and
+    // opening it doesn't need CURRENT to already exist.
+    #[test]
+    fn open_on_empty_directory_starts_with_no_files() {
+        let dir = tempdir();
+        let versions = VersionSet::open(dir.path());
+        assert!(versions.levels.iter().all(|level| level.is_empty()));
+    }
+
+    // Edits logged via log_and_apply must still be visible after the
+    // VersionSet is dropped and reopened from the same directory - this is
+    // the crash-safety property the MANIFEST/CURRENT scheme exists for.
+    #[test]
+    fn replays_logged_edits_after_reopen() {
+        let dir = tempdir();
+        {
+            let mut versions = VersionSet::open(dir.path());
+            versions.log_and_apply(VersionEdit {
+                next_file_number: None,
+                next_seq: None,
+                add_files: vec![(0, meta(1, b"a", b"m"))],
+                remove_files: vec![],
+            });
+            versions.log_and_apply(VersionEdit {
+                next_file_number: None,
+                next_seq: None,
+                add_files: vec![(1, meta(2, b"a", b"z"))],
+                remove_files: vec![],
+            });
+        }
+        let reopened = VersionSet::open(dir.path());
+        assert_eq!(reopened.levels[0].len(), 1);
+        assert_eq!(reopened.levels[0][0].number, 1);
+        assert_eq!(reopened.levels[1].len(), 1);
+        assert_eq!(reopened.levels[1][0].number, 2);
+        // Replay must also restore next_file_number so a reopened table
+        // never reissues a number still referenced by a live file.
+        assert_eq!(reopened.next_file_number, 3);
+    }
+
+    // A later edit removing a file a prior edit added must leave that file
+    // gone after replay, not merely absent from the newest edit.
+    #[test]
+    fn replays_removal_of_a_previously_added_file() {
+        let dir = tempdir();
+        {
+            let mut versions = VersionSet::open(dir.path());
+            versions.log_and_apply(VersionEdit {
+                next_file_number: None,
+                next_seq: None,
+                add_files: vec![(0, meta(1, b"a", b"m")), (0, meta(2, b"n", b"z"))],
+                remove_files: vec![],
+            });
+            versions.log_and_apply(VersionEdit {
+                next_file_number: None,
+                next_seq: None,
+                add_files: vec![(1, meta(3, b"a", b"z"))],
+                remove_files: vec![(0, 1), (0, 2)],
+            });
+        }
+        let reopened = VersionSet::open(dir.path());
+        assert!(reopened.levels[0].is_empty());
+        assert_eq!(reopened.levels[1].len(), 1);
+        assert_eq!(reopened.levels[1][0].number, 3);
+    }
+
+    // A caller's own sequence watermark, logged alongside an edit that adds
+    // no files at all, must still survive a reopen - this is what lets
+    // DiskTable recover next_seq even when a compaction has folded every
+    // commitlog record away (see DiskTable::flush_memtable).
+    #[test]
+    fn replays_a_next_seq_only_edit_with_no_files() {
+        let dir = tempdir();
+        {
+            let mut versions = VersionSet::open(dir.path());
+            versions.log_and_apply(VersionEdit {
+                next_file_number: None,
+                next_seq: Some(42),
+                add_files: vec![],
+                remove_files: vec![],
+            });
+        }
+        let reopened = VersionSet::open(dir.path());
+        assert_eq!(reopened.next_seq, 42);
+    }
+
+    struct TempDir {
+        path: PathBuf,
+    }
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+    impl TempDir {
+        fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+    fn tempdir() -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "rdb-version-test-{}-{}",
+            std::process::id(),
+            next_test_id()
+        ));
+        std::fs::create_dir_all(&path).expect("Failed to create temp dir");
+        TempDir { path }
+    }
+    // A per-call nonce so tests running concurrently in the same process
+    // don't collide on the same temp directory.
+    fn next_test_id() -> u64 {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    }
+}